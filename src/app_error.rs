@@ -6,12 +6,26 @@ use axum::{
 use serde_json::json;
 
 /// 应用错误
-pub struct AppError(pub anyhow::Error);
+pub enum AppError {
+    Internal(anyhow::Error),
+    /// 存储配额不足
+    QuotaExceeded,
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        let msg = format!("服务器异常，{}", self.0);
-        (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+        match self {
+            AppError::Internal(e) => {
+                let msg = format!("服务器异常，{}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+            AppError::QuotaExceeded => {
+                let body = Json(json!({
+                    "error": "storage quota exceeded"
+                }));
+                (StatusCode::PAYLOAD_TOO_LARGE, body).into_response()
+            }
+        }
     }
 }
 
@@ -20,7 +34,7 @@ where
     E: Into<anyhow::Error>,
 {
     fn from(value: E) -> Self {
-        Self(value.into())
+        Self::Internal(value.into())
     }
 }
 
@@ -31,6 +45,7 @@ pub enum AuthError {
     MissingCredentials,
     TokenCreation,
     InvalidToken,
+    Forbidden,
 }
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
@@ -39,6 +54,7 @@ impl IntoResponse for AuthError {
             AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "missing credentials"),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "invalid token"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "token create error"),
+            AuthError::Forbidden => (StatusCode::FORBIDDEN, "insufficient permissions"),
         };
 
         let body = Json(json!({
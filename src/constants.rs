@@ -16,3 +16,19 @@ pub const DATABASE_URL: Lazy<String> = Lazy::new(|| {
     let db_url = std::env::var("DATABASE_URL").expect("Env DATABASE_URL must be set");
     db_url
 });
+
+/// access token 有效期（秒）
+pub const JWT_EXPIRES_IN: Lazy<i64> = Lazy::new(|| {
+    std::env::var("JWT_EXPIRES_IN")
+        .expect("Env JWT_EXPIRES_IN must be set")
+        .parse()
+        .expect("Env JWT_EXPIRES_IN must be an integer number of seconds")
+});
+
+/// refresh token 有效期（秒）
+pub const JWT_MAXAGE: Lazy<i64> = Lazy::new(|| {
+    std::env::var("JWT_MAXAGE")
+        .expect("Env JWT_MAXAGE must be set")
+        .parse()
+        .expect("Env JWT_MAXAGE must be an integer number of seconds")
+});
@@ -1,9 +1,22 @@
 use std::path::PathBuf;
 
+use std::io::Cursor;
+
 use calamine::{open_workbook, RangeDeserializerBuilder, Reader, Xlsx};
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat, Limits};
 
 use crate::model::Record;
 
+/// 头像缩略图的最大边长
+const AVATAR_MAX_SIZE: u32 = 256;
+
+/// 解码头像时允许的最大像素宽高，防止声明超大尺寸的文件（解压炸弹）把
+/// 解码缓冲区撑到数 GB，该限制远小于 DefaultBodyLimit 对原始字节数的限制
+const AVATAR_MAX_PIXELS: u32 = 8192;
+
+/// 解码头像时允许分配的最大内存（字节）
+const AVATAR_MAX_ALLOC: u64 = 64 * 1024 * 1024;
+
 pub fn excel_to_record(excel_path: &PathBuf) -> Result<Vec<Record>, anyhow::Error> {
     let mut excel: Xlsx<_> = open_workbook(excel_path)?;
     let sheet_names = excel.sheet_names();
@@ -52,3 +65,46 @@ pub fn excel_to_record(excel_path: &PathBuf) -> Result<Vec<Record>, anyhow::Erro
 
     Ok(result)
 }
+
+/// 解码头像图片，依据真实文件头（magic bytes）校验格式，
+/// 只接受 PNG/JPEG/WebP，拒绝伪造扩展名或损坏的文件。
+pub fn decode_avatar(bytes: &[u8]) -> Result<DynamicImage, anyhow::Error> {
+    let format = image::guess_format(bytes)?;
+
+    if !matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP
+    ) {
+        anyhow::bail!("不支持的图片格式，仅支持 PNG/JPEG/WebP");
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(AVATAR_MAX_PIXELS);
+    limits.max_image_height = Some(AVATAR_MAX_PIXELS);
+    limits.max_alloc = Some(AVATAR_MAX_ALLOC);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+
+    let image = reader.decode()?;
+
+    Ok(image)
+}
+
+/// 按原始宽高比缩放到不超过 256x256 的缩略图
+pub fn resize_avatar(image: &DynamicImage) -> DynamicImage {
+    image.thumbnail(AVATAR_MAX_SIZE, AVATAR_MAX_SIZE)
+}
+
+/// 将缩略图统一编码为 PNG 并写入磁盘，返回可访问的 URL 路径
+pub fn save_avatar(image: &DynamicImage, user_id: &str) -> Result<String, anyhow::Error> {
+    let dir = PathBuf::from("./upload/avatars");
+    std::fs::create_dir_all(&dir)?;
+
+    let file_name = format!("{}.png", user_id);
+    let file_path = dir.join(&file_name);
+
+    image.save_with_format(&file_path, ImageFormat::Png)?;
+
+    Ok(format!("/upload/avatars/{}", file_name))
+}
@@ -1,29 +1,98 @@
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher};
 use axum::{
     extract::{DefaultBodyLimit, Multipart, Path, State},
+    response::sse::{Event, KeepAlive, Sse},
     routing::{get, post},
     Json, Router,
 };
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use futures::stream::{self, Stream, StreamExt};
 use jsonwebtoken::{encode, Header};
 use rand_core::OsRng;
 use rust_axum_with_vim::{
     app_error::{AppError, AuthError},
-    constants::{DATABASE_URL, KEYS, PORT},
-    file::excel_to_record,
+    constants::{DATABASE_URL, JWT_EXPIRES_IN, JWT_MAXAGE, KEYS, PORT},
+    file::{decode_avatar, excel_to_record, resize_avatar, save_avatar},
     model::{
-        AppState, AuthBody, AuthPayload, Claims, Record, RegisterPayload, Task, TaskBody,
-        TaskStatus, UserBody, UserModel,
+        AppState, AuthBody, AuthPayload, Claims, Record, RefreshTokenModel, RegisterPayload,
+        RequireRole, Role, Task, TaskBody, TaskStatus, UserBody, UserModel,
     },
 };
-use std::{borrow::BorrowMut, collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use validator::Validate;
 
 use serde_json::{json, Value};
 use sqlx::{Pool, Sqlite, SqlitePool};
-use tokio::{fs::File, io::AsyncWriteExt, net::TcpListener, sync::Mutex};
+use time::Duration;
+use tokio::{
+    fs::File,
+    io::AsyncWriteExt,
+    net::TcpListener,
+    sync::{broadcast, Mutex},
+};
+use tower_http::{compression::CompressionLayer, decompression::RequestDecompressionLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
 use uuid::Uuid;
 
+const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+
+/// 新用户默认存储配额（字节），1 GiB
+const DEFAULT_USER_SPACE: i64 = 1024 * 1024 * 1024;
+
+/// OpenAPI 文档
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        login_handler,
+        register_handler,
+        upload_handler,
+        upload_avatar_handler,
+        get_user_handler,
+        show_task_handler,
+        task_events_handler,
+        refresh_handler,
+        logout_handler
+    ),
+    components(schemas(
+        AuthPayload,
+        AuthBody,
+        RegisterPayload,
+        UserBody,
+        TaskBody
+    )),
+    modifiers(&SecurityAddon)
+)]
+struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer_auth",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("JWT")
+                        .build(),
+                ),
+            );
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::registry()
@@ -40,20 +109,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 连接数据库
     let pool = SqlitePool::connect(&DATABASE_URL).await?;
 
-    let app_state = Arc::new(Mutex::new(AppState {
+    let app_state = Arc::new(AppState {
         pool,
-        task: HashMap::new(),
-    }));
+        task: Arc::new(Mutex::new(HashMap::new())),
+        task_events: Arc::new(Mutex::new(HashMap::new())),
+    });
 
-    let app = Router::new()
+    // 常规 JSON/multipart 接口开启请求解压与响应压缩；
+    // SSE 路由必须保持逐块直写，不能被压缩层缓冲，因此单独放在未加压缩层的子路由里
+    let api_routes = Router::new()
         .route("/", get(index_handler))
         .route("/api/login", post(login_handler))
+        .route("/api/refresh", post(refresh_handler))
+        .route("/api/logout", post(logout_handler))
         .route("/api/register", post(register_handler))
         .route("/api/upload", post(upload_handler))
         .route("/api/user/:id", get(get_user_handler))
+        .route("/api/user/avatar", post(upload_avatar_handler))
         .route("/api/task/:task_id", get(show_task_handler))
-        .with_state(Arc::clone(&app_state))
-        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024));
+        // DefaultBodyLimit 必须加在 RequestDecompressionLayer 内层，
+        // 否则校验的是压缩前的字节数，无法防御解压炸弹
+        .layer(DefaultBodyLimit::max(1024 * 1024 * 1024))
+        .layer(CompressionLayer::new())
+        .layer(RequestDecompressionLayer::new());
+
+    let sse_routes = Router::new().route("/api/task/:task_id/events", get(task_events_handler));
+
+    let app = Router::new()
+        .merge(api_routes)
+        .merge(sse_routes)
+        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
+        .with_state(Arc::clone(&app_state));
 
     let url = format!("127.0.0.1:{}", &PORT.to_string());
 
@@ -70,7 +156,14 @@ async fn index_handler() -> Result<(), AppError> {
     Ok(())
 }
 
-// 获取用户信息
+/// 获取用户信息
+#[utoipa::path(
+    get,
+    path = "/api/user/{id}",
+    params(("id" = String, Path, description = "用户 id")),
+    responses((status = 200, description = "用户信息", body = UserBody)),
+    security(("bearer_auth" = []))
+)]
 async fn get_user_handler(_claims: Claims) -> Result<Json<UserBody>, AppError> {
     let user = UserBody::new("katte");
 
@@ -80,11 +173,20 @@ async fn get_user_handler(_claims: Claims) -> Result<Json<UserBody>, AppError> {
 }
 
 /// 注册用户
+#[utoipa::path(
+    post,
+    path = "/api/register",
+    request_body = RegisterPayload,
+    responses(
+        (status = 200, description = "注册成功"),
+        (status = 500, description = "服务器异常")
+    )
+)]
 async fn register_handler(
-    State(app_state): State<Arc<Mutex<AppState>>>,
+    State(app_state): State<Arc<AppState>>,
     Json(body): Json<RegisterPayload>,
 ) -> Result<Json<Value>, AppError> {
-    let pool = &app_state.lock().await.pool;
+    let pool = &app_state.pool;
     let id = Uuid::new_v4().to_string();
 
     let user_exists: Option<bool> =
@@ -95,7 +197,7 @@ async fn register_handler(
 
     if let Some(exists) = user_exists {
         if exists {
-            return Err(AppError(anyhow::anyhow!("用户名已存在")));
+            return Err(AppError::Internal(anyhow::anyhow!("用户名已存在")));
         }
     }
 
@@ -105,12 +207,15 @@ async fn register_handler(
         .map_err(|_| anyhow::anyhow!("hash password error"))?
         .to_string();
 
-    let _result = sqlx::query("INSERT INTO user (id,username,password) values(?,?,?)")
-        .bind(id)
-        .bind(&body.username)
-        .bind(hash_password)
-        .execute(pool)
-        .await?;
+    let _result =
+        sqlx::query("INSERT INTO user (id,username,password,role,space,used) values(?,?,?,?,?,0)")
+            .bind(id)
+            .bind(&body.username)
+            .bind(hash_password)
+            .bind(Role::User.as_str())
+            .bind(DEFAULT_USER_SPACE)
+            .execute(pool)
+            .await?;
 
     let response = json!({
         "status":"success",
@@ -121,21 +226,32 @@ async fn register_handler(
 }
 
 /// 登录
+#[utoipa::path(
+    post,
+    path = "/api/login",
+    request_body = AuthPayload,
+    responses(
+        (status = 200, description = "登录成功，返回访问令牌", body = AuthBody),
+        (status = 400, description = "缺少凭证"),
+        (status = 401, description = "凭证错误")
+    )
+)]
 async fn login_handler(
-    State(app_state): State<Arc<Mutex<AppState>>>,
+    State(app_state): State<Arc<AppState>>,
+    jar: CookieJar,
     Json(body): Json<AuthPayload>,
-) -> Result<Json<AuthBody>, AuthError> {
+) -> Result<(CookieJar, Json<AuthBody>), AuthError> {
     body.validate().map_err(|_e| {
         return AuthError::MissingCredentials;
     })?;
 
     let username = body.username.unwrap();
     let password = body.password.unwrap();
-    let pool = &app_state.lock().await.pool;
+    let pool = &app_state.pool;
 
     let user = sqlx::query_as!(
         UserModel,
-        "select id, username, password from user where username=?",
+        "select id, username, password, role, space, used from user where username=?",
         username
     )
     .fetch_optional(pool)
@@ -152,64 +268,251 @@ async fn login_handler(
 
     let claims = Claims {
         username,
-        exp: 2000000000,
+        role: user.role,
+        exp: now() as usize + *JWT_EXPIRES_IN as usize,
+    };
+
+    let token = encode(&Header::default(), &claims, &KEYS.encoding)
+        .map_err(|_| AuthError::TokenCreation)?;
+
+    let refresh_token = issue_refresh_token(pool, &user.id)
+        .await
+        .map_err(|_| AuthError::TokenCreation)?;
+
+    let jar = jar.add(refresh_token_cookie(refresh_token));
+
+    Ok((jar, Json(AuthBody::new(token))))
+}
+
+/// 刷新 access token
+#[utoipa::path(
+    post,
+    path = "/api/refresh",
+    responses(
+        (status = 200, description = "刷新成功，返回新的访问令牌", body = AuthBody),
+        (status = 400, description = "缺少刷新令牌，或刷新令牌无效、已过期或已吊销")
+    )
+)]
+async fn refresh_handler(
+    State(app_state): State<Arc<AppState>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<AuthBody>), AuthError> {
+    let old_token = jar
+        .get(REFRESH_TOKEN_COOKIE)
+        .map(|c| c.value().to_string())
+        .ok_or(AuthError::MissingCredentials)?;
+
+    let pool = &app_state.pool;
+
+    let stored = sqlx::query_as!(
+        RefreshTokenModel,
+        "select id, user_id, expires_at, revoked from refresh_token where id=?",
+        old_token
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| AuthError::InvalidToken)?
+    .ok_or(AuthError::InvalidToken)?;
+
+    if stored.revoked || stored.expires_at < now() {
+        return Err(AuthError::InvalidToken);
+    }
+
+    let user = sqlx::query_as!(
+        UserModel,
+        "select id, username, password, role, space, used from user where id=?",
+        stored.user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|_| AuthError::InvalidToken)?
+    .ok_or(AuthError::InvalidToken)?;
+
+    // 轮换：旧 refresh token 作废，签发新的
+    sqlx::query!("update refresh_token set revoked=1 where id=?", old_token)
+        .execute(pool)
+        .await
+        .map_err(|_| AuthError::TokenCreation)?;
+
+    let new_refresh_token = issue_refresh_token(pool, &user.id)
+        .await
+        .map_err(|_| AuthError::TokenCreation)?;
+
+    let claims = Claims {
+        username: user.username,
+        role: user.role,
+        exp: now() as usize + *JWT_EXPIRES_IN as usize,
     };
 
     let token = encode(&Header::default(), &claims, &KEYS.encoding)
         .map_err(|_| AuthError::TokenCreation)?;
 
-    Ok(Json(AuthBody::new(token)))
+    let jar = jar.add(refresh_token_cookie(new_refresh_token));
+
+    Ok((jar, Json(AuthBody::new(token))))
+}
+
+/// 注销，撤销 refresh token 并清除 cookie
+#[utoipa::path(
+    post,
+    path = "/api/logout",
+    responses((status = 200, description = "注销成功"))
+)]
+async fn logout_handler(
+    State(app_state): State<Arc<AppState>>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<Value>), AppError> {
+    if let Some(cookie) = jar.get(REFRESH_TOKEN_COOKIE) {
+        let pool = &app_state.pool;
+        let token = cookie.value().to_string();
+        sqlx::query!("update refresh_token set revoked=1 where id=?", token)
+            .execute(pool)
+            .await?;
+    }
+
+    let jar = jar.remove(Cookie::from(REFRESH_TOKEN_COOKIE));
+
+    let response = json!({
+        "status": "success",
+        "message": "已注销",
+    });
+
+    Ok((jar, Json(response)))
+}
+
+/// 当前 unix 时间戳（秒）
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before unix epoch")
+        .as_secs() as i64
+}
+
+/// 签发并持久化一个新的 refresh token，返回其 id
+async fn issue_refresh_token(pool: &Pool<Sqlite>, user_id: &str) -> Result<String, anyhow::Error> {
+    let id = Uuid::new_v4().to_string();
+    let expires_at = now() + *JWT_MAXAGE;
+
+    sqlx::query!(
+        "insert into refresh_token (id, user_id, expires_at, revoked) values (?,?,?,0)",
+        id,
+        user_id,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(id)
+}
+
+/// 构建 HttpOnly 的 refresh token cookie
+fn refresh_token_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((REFRESH_TOKEN_COOKIE, token))
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .path("/api")
+        .max_age(Duration::seconds(*JWT_MAXAGE))
+        .build()
 }
 
 /// 上传数据
+#[utoipa::path(
+    post,
+    path = "/api/upload",
+    request_body(content = String, description = "multipart/form-data 中的 Excel 文件", content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "已创建导入任务"),
+        (status = 403, description = "权限不足，需要管理员角色"),
+        (status = 500, description = "服务器异常")
+    ),
+    security(("bearer_auth" = []))
+)]
 async fn upload_handler(
-    State(app_state): State<Arc<Mutex<AppState>>>,
+    RequireRole(claims): RequireRole<{ Role::Admin as u8 }>,
+    State(app_state): State<Arc<AppState>>,
     mut multipart: Multipart,
 ) -> Result<Json<Value>, AppError> {
-    // let pool = &app_state.lock().await.pool;
-
     while let Some(field) = multipart.next_field().await? {
         let file_name = field.file_name().unwrap().to_string();
         let file_data = field.bytes().await.unwrap();
+        let incoming_size = file_data.len() as i64;
 
-        let path = format!("./upload/{}", &file_name);
-        let file_path = PathBuf::from(&path);
+        let pool = app_state.pool.clone();
 
-        let mut file = File::create(&file_path).await?;
+        let user = sqlx::query_as!(
+            UserModel,
+            "select id, username, password, role, space, used from user where username=?",
+            claims.username
+        )
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("用户不存在"))?;
+
+        // 配额校验与扣减必须是同一条原子语句，否则并发上传会在 check 和 write
+        // 之间产生竞争，导致 used 超过 space 却不返回任何错误
+        let quota_result = sqlx::query!(
+            "update user set used = used + ? where id = ? and used + ? <= space",
+            incoming_size,
+            user.id,
+            incoming_size
+        )
+        .execute(&pool)
+        .await?;
 
-        file.write_all(&file_data).await?;
+        if quota_result.rows_affected() == 0 {
+            return Err(AppError::QuotaExceeded);
+        }
 
-        // excel 转 record
-        let data = excel_to_record(&file_path)?;
+        let path = format!("./upload/{}", &file_name);
+        let file_path = PathBuf::from(&path);
+
+        // 配额在上面已经预扣，后续任何一步失败都要归还，否则用户会在反复
+        // 重试一个坏文件的过程中把配额耗尽，却一次都没有真正导入成功
+        let data = match write_and_parse_upload(&file_path, &file_data).await {
+            Ok(data) => data,
+            Err(e) => {
+                release_quota(&pool, &user.id, incoming_size).await;
+                return Err(AppError::from(e));
+            }
+        };
 
         // 创建任务
         let task = Task::new("导入数据", data.len());
         let id = build_task(Arc::clone(&app_state), task).await;
-        let app_state = Arc::clone(&app_state);
+        let task_map = Arc::clone(&app_state.task);
+        let tx = app_state.task_events.lock().await.get(&id).cloned();
+        let pool = app_state.pool.clone();
+        let user_id = user.id.clone();
 
-        // 导入数据库
+        // 导入数据库：分批开事务插入，每批提交一次再更新进度并广播给 SSE 订阅者，
+        // 避免逐行加锁、逐行提交造成的锁争用和写放大
         tokio::spawn(async move {
-            let mut data_iter = data.iter();
-            while let Some(record) = data_iter.next() {
-                let mut state = app_state.lock().await;
-
-                // 插入
-                insert_excel_record(&state.pool, record).await.unwrap();
-
-                // 标记任务进度
-                let task = state.task.get_mut(&id).unwrap();
-                match task.status {
-                    TaskStatus::Padding(num) => {
-                        let next = num + 1;
-                        if next >= task.total {
-                            task.status = TaskStatus::Done;
-                            break;
-                        } else {
-                            task.status = TaskStatus::Padding(next);
+            let total = data.len();
+            let mut processed = 0usize;
+
+            for chunk in data.chunks(IMPORT_BATCH_SIZE) {
+                if let Err(e) = insert_excel_records(&pool, chunk).await {
+                    release_quota(&pool, &user_id, incoming_size).await;
+                    if let Some(task) = task_map.lock().await.get_mut(&id) {
+                        task.status = TaskStatus::Err(e.to_string());
+                        if let Some(tx) = &tx {
+                            let _ = tx.send(task.to_body());
                         }
                     }
-                    TaskStatus::Done => {}
-                    TaskStatus::Err(ref e) => println!("{:?}", e),
+                    return;
+                }
+
+                processed += chunk.len();
+
+                if let Some(task) = task_map.lock().await.get_mut(&id) {
+                    task.status = if processed >= total {
+                        TaskStatus::Done
+                    } else {
+                        TaskStatus::Padding(processed as u32)
+                    };
+                    if let Some(tx) = &tx {
+                        let _ = tx.send(task.to_body());
+                    }
                 }
             }
         });
@@ -231,35 +534,160 @@ async fn upload_handler(
     Ok(Json(response))
 }
 
+/// 上传头像
+#[utoipa::path(
+    post,
+    path = "/api/user/avatar",
+    request_body(content = String, description = "multipart/form-data 中的头像图片", content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "上传成功，返回头像 URL"),
+        (status = 500, description = "服务器异常")
+    ),
+    security(("bearer_auth" = []))
+)]
+async fn upload_avatar_handler(
+    claims: Claims,
+    State(app_state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> Result<Json<Value>, AppError> {
+    let field = multipart
+        .next_field()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("缺少头像文件"))?;
+
+    let file_data = field.bytes().await?;
+
+    let image = decode_avatar(&file_data)?;
+    let thumbnail = resize_avatar(&image);
+
+    let pool = &app_state.pool;
+
+    let user = sqlx::query_as!(
+        UserModel,
+        "select id, username, password, role, space, used from user where username=?",
+        claims.username
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("用户不存在"))?;
+
+    let url = save_avatar(&thumbnail, &user.id)?;
+
+    sqlx::query!("update user set avatar=? where id=?", url, user.id)
+        .execute(pool)
+        .await?;
+
+    let response = json!({
+        "status": "success",
+        "message": "头像上传成功",
+        "url": url,
+    });
+
+    Ok(Json(response))
+}
+
 /// 查看任务进度
+#[utoipa::path(
+    get,
+    path = "/api/task/{task_id}",
+    params(("task_id" = Uuid, Path, description = "任务 id")),
+    responses((status = 200, description = "任务进度", body = TaskBody)),
+    security(("bearer_auth" = []))
+)]
 async fn show_task_handler(
-    State(app_state): State<Arc<Mutex<AppState>>>,
+    _claims: Claims,
+    State(app_state): State<Arc<AppState>>,
     Path(task_id): Path<Uuid>,
 ) -> Result<Json<TaskBody>, AppError> {
-    let taks_map = &app_state.lock().await.task;
-
-    let task = taks_map.get(&task_id).unwrap();
-    let mut progress = None;
-    let mut err_msg = None;
-    let status = match &task.status {
-        TaskStatus::Done => "done",
-        TaskStatus::Padding(n) => {
-            progress = Some(*n);
-            "padding"
-        }
-        TaskStatus::Err(e) => {
-            err_msg = Some(e.to_string());
-            "error"
-        }
-    };
-    let response = TaskBody {
-        title: task.title.to_string(),
-        status: status.to_string(),
-        progress,
-        total: task.total,
-        err_msg,
-    };
-    Ok(Json(response))
+    let task_map = app_state.task.lock().await;
+    let task = task_map
+        .get(&task_id)
+        .ok_or_else(|| anyhow::anyhow!("任务不存在"))?;
+
+    Ok(Json(task.to_body()))
+}
+
+/// 以 SSE 方式订阅任务导入进度，直至收到 done/error 事件后结束流
+#[utoipa::path(
+    get,
+    path = "/api/task/{task_id}/events",
+    params(("task_id" = Uuid, Path, description = "任务 id")),
+    responses((status = 200, description = "进度事件流，content-type: text/event-stream")),
+    security(("bearer_auth" = []))
+)]
+async fn task_events_handler(
+    _claims: Claims,
+    State(app_state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let rx = app_state
+        .task_events
+        .lock()
+        .await
+        .get(&task_id)
+        .ok_or_else(|| anyhow::anyhow!("任务不存在"))?
+        .subscribe();
+
+    // broadcast 通道不会重放订阅前发出的消息：批量较小的导入可能在客户端
+    // 拿到 task_id 并发起 SSE 请求之前就已经 done/error，此时必须先补发一份
+    // 当前快照，否则客户端会订阅到一个消息已经错过的通道，永远等不到结束事件
+    let snapshot = app_state
+        .task
+        .lock()
+        .await
+        .get(&task_id)
+        .map(|task| task.to_body())
+        .ok_or_else(|| anyhow::anyhow!("任务不存在"))?;
+    let snapshot_done = matches!(snapshot.status.as_str(), "done" | "error");
+
+    enum StreamState {
+        Seed(broadcast::Receiver<TaskBody>, TaskBody, bool),
+        Live(broadcast::Receiver<TaskBody>),
+        Done,
+    }
+
+    // 收到 done/error 事件后终止流，避免客户端无限等待
+    let stream = stream::unfold(
+        StreamState::Seed(rx, snapshot, snapshot_done),
+        |state| async move {
+            match state {
+                StreamState::Seed(rx, body, done) => {
+                    let next = if done {
+                        StreamState::Done
+                    } else {
+                        StreamState::Live(rx)
+                    };
+                    Some((body, next))
+                }
+                StreamState::Live(mut rx) => loop {
+                    match rx.recv().await {
+                        Ok(body) => {
+                            let finished = matches!(body.status.as_str(), "done" | "error");
+                            let next = if finished {
+                                StreamState::Done
+                            } else {
+                                StreamState::Live(rx)
+                            };
+                            break Some((body, next));
+                        }
+                        // 消费过慢错过的中间进度直接跳过，继续等待后续事件
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(_) => break None,
+                    }
+                },
+                StreamState::Done => None,
+            }
+        },
+    )
+    .map(|body| {
+        let event = Event::default()
+            .event(body.status.clone())
+            .json_data(body)
+            .unwrap();
+        Ok(event)
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
 /// 密码验证
@@ -274,50 +702,100 @@ fn verify_password(password: String, password_hash: &String) -> Result<(), Strin
         })
 }
 
-/// 创建任务
-async fn build_task(app_state: Arc<Mutex<AppState>>, task: Task) -> Uuid {
+/// 每个任务的进度事件广播通道容量
+const TASK_EVENT_CHANNEL_CAPACITY: usize = 16;
+
+/// 落盘并解析上传的 Excel 文件，任一环节失败都原样返回错误，交由调用方归还配额
+async fn write_and_parse_upload(
+    file_path: &PathBuf,
+    file_data: &[u8],
+) -> Result<Vec<Record>, anyhow::Error> {
+    let mut file = File::create(file_path).await?;
+    file.write_all(file_data).await?;
+
+    excel_to_record(file_path)
+}
+
+/// 归还预扣的存储配额，用于文件落盘/解析/导入失败时的补偿
+async fn release_quota(pool: &Pool<Sqlite>, user_id: &str, amount: i64) {
+    if let Err(e) = sqlx::query!(
+        "update user set used = used - ? where id = ?",
+        amount,
+        user_id
+    )
+    .execute(pool)
+    .await
+    {
+        tracing::error!(
+            "归还配额失败 user_id={} amount={} err={}",
+            user_id,
+            amount,
+            e
+        );
+    }
+}
+
+/// 创建任务，同时为其建立一个进度事件广播通道
+async fn build_task(app_state: Arc<AppState>, task: Task) -> Uuid {
     let id = Uuid::new_v4();
-    app_state.lock().await.task.borrow_mut().insert(id, task);
+    let (tx, _rx) = broadcast::channel(TASK_EVENT_CHANNEL_CAPACITY);
+
+    app_state.task.lock().await.insert(id, task);
+    app_state.task_events.lock().await.insert(id, tx);
+
     id
 }
 
-async fn insert_excel_record(pool: &Pool<Sqlite>, record: &Record) -> Result<(), anyhow::Error> {
-    let id = Uuid::new_v4().to_string();
+/// 单批导入的记录数，单个事务内提交
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// 在一个事务内批量插入一批记录，批内全部成功或全部回滚
+async fn insert_excel_records(
+    pool: &Pool<Sqlite>,
+    records: &[Record],
+) -> Result<(), anyhow::Error> {
+    let mut tx = pool.begin().await?;
+
+    for record in records {
+        let id = Uuid::new_v4().to_string();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO domain
+                (
+                id, domain_name, domain_age, order_no, language,
+                title, score, dns, registrar_name, registrar_address,
+                registrar_by, registrar_at, email, expire_at,
+                record_status, record_at, record_main_body, record_type, record_no,
+                record_name
+                ) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
+            "#,
+            id,
+            record.domain_name,
+            record.age,
+            record.order_no,
+            record.language,
+            record.title,
+            record.score,
+            record.dns,
+            record.registrar_name,
+            record.registrar_address,
+            record.registrar_by,
+            record.registrar_at,
+            record.email,
+            record.expire_at,
+            record.record_status,
+            record.record_at,
+            record.record_main_body,
+            record.record_type,
+            record.record_no,
+            record.record_name
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
 
-    let _result = sqlx::query!(
-        r#"
-            INSERT INTO domain
-            (
-            id, domain_name, domain_age, order_no, language,
-            title, score, dns, registrar_name, registrar_address,
-            registrar_by, registrar_at, email, expire_at,
-            record_status, record_at, record_main_body, record_type, record_no,
-            record_name
-            ) values(?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
-        "#,
-        id,
-        record.domain_name,
-        record.age,
-        record.order_no,
-        record.language,
-        record.title,
-        record.score,
-        record.dns,
-        record.registrar_name,
-        record.registrar_address,
-        record.registrar_by,
-        record.registrar_at,
-        record.email,
-        record.expire_at,
-        record.record_status,
-        record.record_at,
-        record.record_main_body,
-        record.record_type,
-        record.record_no,
-        record.record_name
-    )
-    .execute(pool)
-    .await?;
+    tx.commit().await?;
 
     Ok(())
 }
@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::HashMap, fmt::Display, sync::Arc};
 
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts, RequestPartsExt};
 use axum_extra::{
@@ -11,6 +11,8 @@ use jsonwebtoken::{decode, DecodingKey, EncodingKey, Validation};
 
 use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::{Pool, Sqlite};
+use tokio::sync::{broadcast, Mutex};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{app_error::AuthError, constants::KEYS};
@@ -30,7 +32,7 @@ pub struct Task {
     pub status: TaskStatus,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct TaskBody {
     pub title: String,
     pub total: u32,
@@ -47,15 +49,44 @@ impl Task {
             status: TaskStatus::Padding(0),
         }
     }
+
+    /// 转换为对外暴露的进度快照，供轮询接口和 SSE 事件复用同一套表示
+    pub fn to_body(&self) -> TaskBody {
+        let mut progress = None;
+        let mut err_msg = None;
+        let status = match &self.status {
+            TaskStatus::Done => "done",
+            TaskStatus::Padding(n) => {
+                progress = Some(*n);
+                "padding"
+            }
+            TaskStatus::Err(e) => {
+                err_msg = Some(e.clone());
+                "error"
+            }
+        };
+
+        TaskBody {
+            title: self.title.clone(),
+            total: self.total,
+            status: status.to_string(),
+            progress,
+            err_msg,
+        }
+    }
 }
 
+/// 共享应用状态。`pool` 内部已是 `Arc`，可直接克隆，不再需要外层全局锁；
+/// `task` 单独持有一把细粒度的锁，避免任务进度更新阻塞数据库访问。
+/// `task_events` 为每个任务保存一个广播通道，供 SSE 接口订阅实时进度。
 pub struct AppState {
     pub pool: Pool<Sqlite>,
-    pub task: HashMap<Uuid, Task>,
+    pub task: Arc<Mutex<HashMap<Uuid, Task>>>,
+    pub task_events: Arc<Mutex<HashMap<Uuid, broadcast::Sender<TaskBody>>>>,
 }
 
 /// 授权请求参数
-#[derive(Debug, Serialize, Deserialize, Validate)]
+#[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
 pub struct AuthPayload {
     #[validate(required)]
     pub username: Option<String>,
@@ -64,7 +95,7 @@ pub struct AuthPayload {
 }
 
 /// 授权响应参数
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct AuthBody {
     pub access_token: String,
     pub token_type: String,
@@ -79,7 +110,7 @@ impl AuthBody {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserBody {
     pub name: String,
     pub age: u8,
@@ -97,7 +128,7 @@ impl UserBody {
 }
 
 /// 注册参数
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterPayload {
     pub username: String,
     pub password: String,
@@ -107,6 +138,7 @@ pub struct RegisterPayload {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub username: String,
+    pub role: String,
     pub exp: usize,
 }
 
@@ -135,6 +167,65 @@ where
     }
 }
 
+/// 角色 / 权限等级，数值越大权限越高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Role {
+    User = 0,
+    Admin = 1,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = AuthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            _ => Err(AuthError::InvalidToken),
+        }
+    }
+}
+
+/// 角色校验提取器，`R` 为该路由要求的最低权限等级（即 `Role as u8`）。
+/// 内部先走一遍 `Claims` 的解析逻辑，再比对角色，权限不足时拒绝为 403。
+pub struct RequireRole<const R: u8>(pub Claims);
+
+#[async_trait]
+impl<S, const R: u8> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+
+        let role: Role = claims.role.parse()?;
+
+        if (role as u8) < R {
+            return Err(AuthError::Forbidden);
+        }
+
+        Ok(RequireRole(claims))
+    }
+}
+
 pub struct Keys {
     pub encoding: EncodingKey,
     pub decoding: DecodingKey,
@@ -154,6 +245,20 @@ pub struct UserModel {
     pub id: String,
     pub username: String,
     pub password: String,
+    pub role: String,
+    /// 存储配额（字节）
+    pub space: i64,
+    /// 已使用的存储空间（字节）
+    pub used: i64,
+}
+
+/// refresh token 持久化记录，用于轮换与吊销
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenModel {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: i64,
+    pub revoked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]